@@ -0,0 +1,43 @@
+use rust_embed::RustEmbed;
+use warp::{
+    http::{
+        header,
+        Response,
+    },
+    Filter,
+    Rejection,
+    Reply,
+};
+
+/// The built web UI, embedded into the server binary so `Bundled` deployments
+/// are a single self-contained executable. Populated by the frontend's build
+/// step writing into `web/dist` before `cargo build`.
+#[derive(RustEmbed)]
+#[folder = "web/dist"]
+struct Assets;
+
+/// Serve the embedded web UI, falling back to `index.html` for any path that
+/// isn't a known asset so client-side routing keeps working on refresh/deep
+/// links.
+pub fn serve_embedded() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path::tail().and_then(|tail: warp::path::Tail| async move {
+        let path = tail.as_str();
+        let path = if path.is_empty() { "index.html" } else { path };
+
+        let (path, asset) = match Assets::get(path) {
+            Some(asset) => (path, Some(asset)),
+            None => ("index.html", Assets::get("index.html")),
+        };
+
+        match asset {
+            Some(asset) => {
+                let mime = mime_guess::from_path(path).first_or_octet_stream();
+                Ok(Response::builder()
+                    .header(header::CONTENT_TYPE, mime.as_ref())
+                    .body(asset.data.into_owned())
+                    .expect("response to be well-formed"))
+            }
+            None => Err(warp::reject::not_found()),
+        }
+    })
+}
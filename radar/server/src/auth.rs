@@ -0,0 +1,89 @@
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use subtle::ConstantTimeEq;
+
+/// A publisher token the server will accept on `InitializePublish`.
+#[derive(Debug, Clone)]
+pub struct PublisherToken {
+    pub token: String,
+    /// Unix timestamp (seconds) after which the token is no longer valid.
+    /// `None` means the token never expires.
+    pub expires_at: Option<u64>,
+}
+
+impl PublisherToken {
+    pub fn is_valid(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now_unix() < expires_at,
+            None => true,
+        }
+    }
+
+    /// Whether `candidate` is this token's secret. Compared in constant
+    /// time so a client probing `InitializePublish` repeatedly can't use
+    /// response timing to brute-force the token byte-by-byte.
+    pub fn matches(&self, candidate: &str) -> bool {
+        self.token.as_bytes().ct_eq(candidate.as_bytes()).into()
+    }
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_without_expiry_never_expires() {
+        let token = PublisherToken {
+            token: "abc".to_string(),
+            expires_at: None,
+        };
+        assert!(token.is_valid());
+    }
+
+    #[test]
+    fn token_with_future_expiry_is_valid() {
+        let token = PublisherToken {
+            token: "abc".to_string(),
+            expires_at: Some(now_unix() + 3600),
+        };
+        assert!(token.is_valid());
+    }
+
+    #[test]
+    fn token_with_past_expiry_is_invalid() {
+        let token = PublisherToken {
+            token: "abc".to_string(),
+            expires_at: Some(now_unix().saturating_sub(3600)),
+        };
+        assert!(!token.is_valid());
+    }
+
+    #[test]
+    fn matches_accepts_the_exact_token() {
+        let token = PublisherToken {
+            token: "s3cr3t".to_string(),
+            expires_at: None,
+        };
+        assert!(token.matches("s3cr3t"));
+    }
+
+    #[test]
+    fn matches_rejects_a_wrong_or_differently_sized_token() {
+        let token = PublisherToken {
+            token: "s3cr3t".to_string(),
+            expires_at: None,
+        };
+        assert!(!token.matches("s3cr3u"));
+        assert!(!token.matches("s3cr3t-but-longer"));
+    }
+}
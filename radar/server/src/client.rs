@@ -0,0 +1,122 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+use radar_shared::protocol::S2CMessage;
+use tokio::sync::{
+    mpsc,
+    Notify,
+    RwLock,
+};
+
+use crate::{
+    codec::Codec,
+    ClientState,
+};
+
+/// Tears down a connection's rx/tx loops from outside. A bare `Notify`
+/// would lose the signal if `signal()` is called before the loop has
+/// reached its next `.notified()` await (`notify_waiters` only wakes
+/// already-parked waiters); the `closed` flag makes the signal durable so a
+/// loop iteration that arrives after the fact still sees it.
+#[derive(Default)]
+pub struct Shutdown {
+    closed: AtomicBool,
+    notify: Notify,
+}
+
+impl Shutdown {
+    pub fn signal(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_signalled(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Resolves immediately if `signal()` already happened, otherwise waits
+    /// for it. Safe to call again every loop iteration: a signal that
+    /// arrived between iterations isn't missed.
+    pub async fn wait(&self) {
+        /* created before the flag check: a `Notified` receives
+         * `notify_waiters` from the moment it exists, so a `signal()`
+         * landing between the check and the await still wakes it */
+        let notified = self.notify.notified();
+        if self.is_signalled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+pub struct PubClient {
+    pub client_id: u32,
+    pub address: SocketAddr,
+    pub state: ClientState,
+
+    pub tx: mpsc::Sender<S2CMessage>,
+
+    /// The wire codec negotiated for this connection (`NegotiateEncoding`).
+    /// Shared with the tx loop so a negotiation mid-session takes effect on
+    /// the very next broadcast.
+    pub codec: Arc<RwLock<Codec>>,
+    /// Whether `NegotiateEncoding` has already been processed for this
+    /// connection. It may only happen once, since the server has no way to
+    /// decode a later renegotiation attempt sent in the (by then outdated)
+    /// previously negotiated codec.
+    pub encoding_negotiated: bool,
+
+    /// Set once the client has completed the `Version` handshake.
+    /// `ServerCommandHandler` gates every other command behind this.
+    pub protocol_version: Option<u32>,
+
+    /// Signalled to tear down this connection's websocket tasks (rx/tx
+    /// loops) from outside, e.g. when `ServerCommandHandler` rejects an
+    /// incompatible `Version` and needs the socket to actually close.
+    pub shutdown: Arc<Shutdown>,
+}
+
+impl PubClient {
+    pub fn new(
+        tx: mpsc::Sender<S2CMessage>,
+        address: SocketAddr,
+        codec: Arc<RwLock<Codec>>,
+        shutdown: Arc<Shutdown>,
+    ) -> Self {
+        Self {
+            client_id: 0,
+            address,
+            state: ClientState::Uninitialized,
+
+            tx,
+            codec,
+            encoding_negotiated: false,
+            protocol_version: None,
+            shutdown,
+        }
+    }
+
+    /// Send a command response back to the client, if any.
+    pub fn send_command(&self, message: Option<S2CMessage>) {
+        let message = match message {
+            Some(message) => message,
+            None => return,
+        };
+
+        if let Err(err) = self.tx.try_send(message) {
+            log::warn!(
+                "Failed to send command response to client {}: {}",
+                self.client_id,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        Weak,
+    },
+};
+
+use futures_util::{
+    SinkExt,
+    StreamExt,
+};
+use radar_shared::protocol::{
+    C2SMessage,
+    S2CMessage,
+    SessionLocationResult,
+    PROTOCOL_VERSION,
+};
+use rand::{
+    distributions::Alphanumeric,
+    Rng,
+};
+use tokio::sync::{
+    mpsc,
+    oneshot,
+    RwLock,
+};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use crate::server::RadarServer;
+
+/// Static configuration for one peer node in the cluster.
+#[derive(Debug, Clone)]
+pub struct PeerNodeConfig {
+    pub node_id: String,
+    /// Websocket address of the peer's client endpoint, e.g.
+    /// `ws://radar-eu.example.com:9001/subscribe`.
+    pub address: String,
+    /// Shared secret presented to the peer with every `LocateSession` query
+    /// (and expected on inbound ones) so only trusted nodes can locate
+    /// sessions to relay.
+    pub token: String,
+}
+
+/// Cluster-wide configuration for a [`RadarServer`].
+#[derive(Debug, Clone, Default)]
+pub struct ClusterConfig {
+    /// This node's own id, used to namespace session ids so two nodes can
+    /// never generate a colliding one.
+    pub node_id: String,
+    pub peers: Vec<PeerNodeConfig>,
+}
+
+struct PeerLink {
+    config: PeerNodeConfig,
+    tx: mpsc::Sender<C2SMessage>,
+}
+
+/// Maintains outbound websocket links to every configured peer, resolves
+/// which node hosts a given session and relays that node's broadcasts into
+/// the local subscribers watching it.
+pub struct ClusterManager {
+    ref_self: Weak<RwLock<ClusterManager>>,
+    server: Weak<RwLock<RadarServer>>,
+
+    node_id: String,
+    peers: BTreeMap<String, PeerLink>,
+    pending_locates: BTreeMap<String, oneshot::Sender<SessionLocationResult>>,
+}
+
+impl ClusterManager {
+    pub fn new(server: Weak<RwLock<RadarServer>>, config: ClusterConfig) -> Arc<RwLock<Self>> {
+        let mut result = Self {
+            ref_self: Default::default(),
+            server,
+
+            node_id: config.node_id,
+            peers: Default::default(),
+            pending_locates: Default::default(),
+        };
+
+        let cluster = Arc::new_cyclic(|weak| {
+            result.ref_self = weak.clone();
+            RwLock::new(result)
+        });
+
+        for peer in config.peers {
+            let cluster = cluster.clone();
+            tokio::spawn(async move {
+                cluster.write().await.connect_peer(peer).await;
+            });
+        }
+
+        cluster
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Generate a session id namespaced with this node's id so ids minted by
+    /// different nodes in the cluster can never collide.
+    pub fn new_session_id(&self) -> String {
+        let suffix = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(6)
+            .collect::<String>();
+
+        format!("{}-{}", self.node_id, suffix)
+    }
+
+    /// The node id a namespaced session id was minted on, if it looks like
+    /// one of ours (i.e. contains the `<node_id>-<suffix>` separator).
+    pub fn owning_node(session_id: &str) -> Option<&str> {
+        session_id.rsplit_once('-').map(|(node_id, _)| node_id)
+    }
+
+    /// Whether `token` matches the configured secret for one of our peers.
+    /// Used to authenticate inbound `LocateSession` queries, which arrive
+    /// over the same websocket route as ordinary clients.
+    pub fn is_valid_peer_token(&self, token: &str) -> bool {
+        self.peers.values().any(|peer| peer.config.token == token)
+    }
+
+    async fn connect_peer(&mut self, peer: PeerNodeConfig) {
+        let (socket, _) = match tokio_tungstenite::connect_async(&peer.address).await {
+            Ok(result) => result,
+            Err(err) => {
+                log::warn!("Failed to connect to cluster peer {}: {}", peer.address, err);
+                return;
+            }
+        };
+
+        let (mut ws_tx, mut ws_rx) = socket.split();
+        let (tx, mut rx) = mpsc::channel::<C2SMessage>(16);
+
+        let cluster = self.ref_self.clone();
+        let peer_node_id = peer.node_id.clone();
+        let node_id = self.node_id.clone();
+        tokio::spawn(async move {
+            // The peer's server treats this link like any other client
+            // connection, so it expects the same handshake first.
+            let handshake = C2SMessage::Version {
+                request_id: None,
+                client_version: format!("cluster-node-{}", node_id),
+                protocol_version: PROTOCOL_VERSION,
+            };
+            let Ok(encoded) = serde_json::to_string(&handshake) else {
+                return;
+            };
+            if ws_tx.send(WsMessage::text(encoded)).await.is_err() {
+                return;
+            }
+
+            loop {
+                tokio::select! {
+                    outgoing = rx.recv() => {
+                        let Some(message) = outgoing else { break; };
+                        let Ok(encoded) = serde_json::to_string(&message) else { continue; };
+                        if ws_tx.send(WsMessage::text(encoded)).await.is_err() {
+                            break;
+                        }
+                    }
+                    incoming = ws_rx.next() => {
+                        let Some(Ok(message)) = incoming else { break; };
+                        if !message.is_text() {
+                            continue;
+                        }
+
+                        let Ok(message) = serde_json::from_slice::<S2CMessage>(message.as_bytes()) else {
+                            continue;
+                        };
+
+                        if let Some(cluster) = cluster.upgrade() {
+                            cluster.write().await.handle_peer_message(&peer_node_id, message).await;
+                        }
+                    }
+                }
+            }
+
+            log::info!("Cluster link to {} closed", peer_node_id);
+        });
+
+        log::info!("Connected to cluster peer {} ({})", peer.node_id, peer.address);
+        self.peers.insert(peer.node_id.clone(), PeerLink { config: peer, tx });
+    }
+
+    async fn handle_peer_message(&mut self, origin_node_id: &str, message: S2CMessage) {
+        match message {
+            S2CMessage::SessionLocation {
+                request_id: Some(request_id),
+                result,
+                ..
+            } => {
+                if let Some(waiter) = self.pending_locates.remove(&request_id) {
+                    let _ = waiter.send(result);
+                }
+            }
+            S2CMessage::NotifyState { session_id, state } => {
+                if let Some(server) = self.server.upgrade() {
+                    if let Some(session) = server.read().await.pub_session_find(&session_id) {
+                        session.broadcast(&S2CMessage::NotifyState { session_id, state });
+                    }
+                }
+            }
+            S2CMessage::NotifySessionClosed { session_id } => {
+                if let Some(server) = self.server.upgrade() {
+                    server
+                        .write()
+                        .await
+                        .pub_session_close_relay(&session_id)
+                        .await;
+                }
+            }
+            S2CMessage::NotifyViewCount { session_id, viewers } => {
+                if let Some(server) = self.server.upgrade() {
+                    server
+                        .read()
+                        .await
+                        .pub_session_relay_view_count(&session_id, viewers);
+                }
+            }
+            other => {
+                log::debug!("Unhandled cluster message from {}: {:?}", origin_node_id, other);
+            }
+        }
+    }
+
+    /// Ask the given peer to start relaying broadcasts for `session_id` to
+    /// us. `secret` is forwarded so the peer's own subscribe check passes if
+    /// the session turns out to be private. Fire-and-forget: broadcasts
+    /// simply start arriving via `handle_peer_message` once the peer
+    /// processes the subscription.
+    pub async fn subscribe_remote(&self, node_id: &str, session_id: &str, secret: Option<&str>) {
+        let Some(peer) = self.peers.get(node_id) else {
+            log::warn!("Tried to relay session {} from unknown peer {}", session_id, node_id);
+            return;
+        };
+
+        let _ = peer
+            .tx
+            .send(C2SMessage::InitializeSubscribe {
+                request_id: None,
+                session_id: session_id.to_string(),
+                secret: secret.map(str::to_string),
+            })
+            .await;
+    }
+
+    /// Tell the given peer we're no longer relaying `session_id`, so it can
+    /// drop us as a subscriber. Mirrors `subscribe_remote`.
+    pub async fn unsubscribe_remote(&self, node_id: &str, session_id: &str) {
+        let Some(peer) = self.peers.get(node_id) else {
+            return;
+        };
+
+        let _ = peer
+            .tx
+            .send(C2SMessage::UnsubscribeSession {
+                request_id: None,
+                session_id: session_id.to_string(),
+            })
+            .await;
+    }
+
+    /// Ask whichever peer owns `session_id` (per its `<node_id>-<suffix>`
+    /// namespace) whether it hosts the publisher and, if the session is
+    /// private, whether `secret` authorizes us to relay it. Queries only
+    /// that one peer instead of broadcasting to all of them, so there's no
+    /// race between a fast "not found" from an uninvolved peer and a slower
+    /// positive reply from the actual owner.
+    pub async fn locate_session(
+        &mut self,
+        session_id: &str,
+        secret: Option<&str>,
+    ) -> SessionLocationResult {
+        let Some(owner_node_id) = Self::owning_node(session_id) else {
+            return SessionLocationResult::NotFound;
+        };
+
+        let Some(peer) = self.peers.get(owner_node_id) else {
+            return SessionLocationResult::NotFound;
+        };
+
+        let request_id = self.new_session_id();
+        let (tx, rx) = oneshot::channel();
+        self.pending_locates.insert(request_id.clone(), tx);
+
+        let _ = peer
+            .tx
+            .send(C2SMessage::LocateSession {
+                request_id: Some(request_id.clone()),
+                session_id: session_id.to_string(),
+                cluster_token: peer.config.token.clone(),
+                secret: secret.map(str::to_string),
+            })
+            .await;
+
+        match tokio::time::timeout(std::time::Duration::from_secs(2), rx).await {
+            Ok(Ok(result)) => result,
+            _ => {
+                self.pending_locates.remove(&request_id);
+                SessionLocationResult::NotFound
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owning_node_parses_the_node_id_prefix() {
+        assert_eq!(ClusterManager::owning_node("eu-1-aZ3xQ9"), Some("eu-1"));
+    }
+
+    #[test]
+    fn owning_node_is_none_without_a_separator() {
+        assert_eq!(ClusterManager::owning_node("aZ3xQ9"), None);
+    }
+}
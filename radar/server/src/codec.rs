@@ -0,0 +1,132 @@
+use flate2::{
+    write::DeflateEncoder,
+    Compression,
+};
+use radar_shared::protocol::{
+    C2SMessage,
+    Encoding,
+    S2CMessage,
+};
+use std::io::Write;
+
+/// Server preference order when several encodings are mutually supported.
+/// Binary codecs are cheaper to encode/decode and smaller on the wire than
+/// JSON, so they're preferred whenever the client offers them.
+const SERVER_PRIORITY: [Encoding; 3] = [Encoding::Cbor, Encoding::MessagePack, Encoding::Json];
+
+/// The wire format negotiated for a single connection via
+/// `C2SMessage::NegotiateEncoding`. Defaults to plain JSON text so clients
+/// that never negotiate keep working unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Codec {
+    pub encoding: Encoding,
+    pub deflate: bool,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Json,
+            deflate: false,
+        }
+    }
+}
+
+impl Codec {
+    pub fn negotiate(offered: &[Encoding], deflate: bool) -> Self {
+        let encoding = SERVER_PRIORITY
+            .into_iter()
+            .find(|encoding| offered.contains(encoding))
+            .unwrap_or(Encoding::Json);
+
+        Self { encoding, deflate }
+    }
+
+    /// Whether this codec must be framed as a binary websocket message
+    /// rather than text (plain, uncompressed JSON is the only text-safe
+    /// combination).
+    pub fn is_binary(&self) -> bool {
+        self.encoding != Encoding::Json || self.deflate
+    }
+
+    pub fn encode(&self, message: &S2CMessage) -> anyhow::Result<Vec<u8>> {
+        let payload = match self.encoding {
+            Encoding::Json => serde_json::to_vec(message)?,
+            Encoding::MessagePack => rmp_serde::to_vec(message)?,
+            Encoding::Cbor => serde_cbor::to_vec(message)?,
+        };
+
+        if self.deflate {
+            deflate_compress(&payload)
+        } else {
+            Ok(payload)
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> anyhow::Result<C2SMessage> {
+        let owned;
+        let payload = if self.deflate {
+            owned = deflate_decompress(bytes)?;
+            owned.as_slice()
+        } else {
+            bytes
+        };
+
+        Ok(match self.encoding {
+            Encoding::Json => serde_json::from_slice(payload)?,
+            Encoding::MessagePack => rmp_serde::from_slice(payload)?,
+            Encoding::Cbor => serde_cbor::from_slice(payload)?,
+        })
+    }
+}
+
+fn deflate_compress(payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(payload)?;
+    Ok(encoder.finish()?)
+}
+
+fn deflate_decompress(payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    let mut decoder = DeflateDecoder::new(payload);
+    let mut result = Vec::new();
+    decoder.read_to_end(&mut result)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_prefers_cbor_over_messagepack_and_json() {
+        let codec = Codec::negotiate(&[Encoding::Json, Encoding::MessagePack, Encoding::Cbor], false);
+        assert_eq!(codec.encoding, Encoding::Cbor);
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_json_when_nothing_else_is_offered() {
+        let codec = Codec::negotiate(&[], false);
+        assert_eq!(codec.encoding, Encoding::Json);
+    }
+
+    #[test]
+    fn negotiate_picks_messagepack_when_cbor_is_not_offered() {
+        let codec = Codec::negotiate(&[Encoding::Json, Encoding::MessagePack], false);
+        assert_eq!(codec.encoding, Encoding::MessagePack);
+    }
+
+    #[test]
+    fn json_without_deflate_is_not_binary() {
+        let codec = Codec::negotiate(&[Encoding::Json], false);
+        assert!(!codec.is_binary());
+    }
+
+    #[test]
+    fn deflated_json_is_binary() {
+        let codec = Codec::negotiate(&[Encoding::Json], true);
+        assert!(codec.is_binary());
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use radar_shared::protocol::{
+    C2SMessage,
+    S2CMessage,
+    SessionLocationResult,
+    MIN_SUPPORTED_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
+use tokio::sync::RwLock;
+
+use crate::{
+    client::PubClient,
+    codec::Codec,
+    server::{
+        PubSessionCreateResult,
+        PubSessionSubscribeResult,
+        RadarServer,
+    },
+    ClientState,
+};
+
+/// The server's own version string, reported in `S2CMessage::Version`.
+const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub struct ServerCommandHandler {
+    pub server: Arc<RwLock<RadarServer>>,
+    pub client: Arc<RwLock<PubClient>>,
+    pub client_id: u32,
+}
+
+/// Result of handling one `C2SMessage`: the response to send back (if any),
+/// and whether the connection should be closed afterwards. Used to let the
+/// version handshake reject an incompatible client without the rest of the
+/// match arms having to know about connection teardown.
+pub struct CommandOutcome {
+    pub response: Option<S2CMessage>,
+    pub close: bool,
+}
+
+impl CommandOutcome {
+    fn reply(response: S2CMessage) -> Self {
+        Self {
+            response: Some(response),
+            close: false,
+        }
+    }
+
+    fn none() -> Self {
+        Self {
+            response: None,
+            close: false,
+        }
+    }
+
+    fn close_with(response: S2CMessage) -> Self {
+        Self {
+            response: Some(response),
+            close: true,
+        }
+    }
+}
+
+impl ServerCommandHandler {
+    pub async fn handle_command(&self, command: C2SMessage) -> CommandOutcome {
+        let request_id = command.request_id().map(str::to_string);
+
+        if !matches!(command, C2SMessage::Version { .. })
+            && self.client.read().await.protocol_version.is_none()
+        {
+            return CommandOutcome::reply(S2CMessage::Error {
+                request_id,
+                message: "client must send Version before any other command".to_string(),
+            });
+        }
+
+        match command {
+            C2SMessage::Version {
+                client_version,
+                protocol_version,
+                ..
+            } => {
+                if protocol_version < MIN_SUPPORTED_PROTOCOL_VERSION {
+                    return CommandOutcome::close_with(S2CMessage::Error {
+                        request_id,
+                        message: format!(
+                            "protocol version {} is no longer supported, server requires >= {}",
+                            protocol_version, MIN_SUPPORTED_PROTOCOL_VERSION
+                        ),
+                    });
+                }
+
+                log::debug!(
+                    "Client {} ({}) negotiated protocol version {}",
+                    self.client_id,
+                    client_version,
+                    protocol_version
+                );
+                self.client.write().await.protocol_version = Some(protocol_version);
+
+                CommandOutcome::reply(S2CMessage::Version {
+                    request_id,
+                    server_version: SERVER_VERSION.to_string(),
+                    protocol_version: PROTOCOL_VERSION,
+                    capabilities: self.server.read().await.capabilities(),
+                })
+            }
+            C2SMessage::InitializePublish { token, private, .. } => {
+                let result = self
+                    .server
+                    .write()
+                    .await
+                    .pub_session_create(self.client_id, &token, private)
+                    .await;
+
+                CommandOutcome::reply(match result {
+                    PubSessionCreateResult::Success {
+                        session_id,
+                        subscribe_secret,
+                    } => S2CMessage::ResponsePublishSession {
+                        request_id,
+                        session_id,
+                        subscribe_secret,
+                    },
+                    PubSessionCreateResult::InvalidClientState => S2CMessage::Error {
+                        request_id,
+                        message: "client is already publishing or subscribed".to_string(),
+                    },
+                    PubSessionCreateResult::Unauthorized => S2CMessage::Error {
+                        request_id,
+                        message: "invalid or expired publisher token".to_string(),
+                    },
+                })
+            }
+            C2SMessage::InitializeSubscribe {
+                session_id, secret, ..
+            } => {
+                let result = self
+                    .server
+                    .write()
+                    .await
+                    .pub_session_subscribe(&session_id, self.client_id, secret.as_deref())
+                    .await;
+
+                CommandOutcome::reply(match result {
+                    PubSessionSubscribeResult::Success => S2CMessage::ResponseSubscribeSession {
+                        request_id,
+                        session_id,
+                    },
+                    PubSessionSubscribeResult::InvalidClientState => S2CMessage::Error {
+                        request_id,
+                        message: "client is currently publishing a session".to_string(),
+                    },
+                    PubSessionSubscribeResult::InvalidSessionId => S2CMessage::Error {
+                        request_id,
+                        message: format!("unknown session id {}", session_id),
+                    },
+                    PubSessionSubscribeResult::InvalidClientId => S2CMessage::Error {
+                        request_id,
+                        message: "client no longer connected".to_string(),
+                    },
+                    PubSessionSubscribeResult::Unauthorized => S2CMessage::Error {
+                        request_id,
+                        message: "invalid or missing subscribe secret".to_string(),
+                    },
+                })
+            }
+            C2SMessage::UnsubscribeSession { session_id, .. } => {
+                self.server
+                    .write()
+                    .await
+                    .pub_session_unsubscribe(&session_id, self.client_id)
+                    .await;
+
+                CommandOutcome::reply(S2CMessage::ResponseUnsubscribeSession {
+                    request_id,
+                    session_id,
+                })
+            }
+            C2SMessage::PublishState { state, .. } => {
+                let client_state = self.client.read().await.state.clone();
+                let session_id = match client_state {
+                    ClientState::Publisher { session_id } => session_id,
+                    _ => {
+                        return CommandOutcome::reply(S2CMessage::Error {
+                            request_id,
+                            message: "client is not publishing a session".to_string(),
+                        })
+                    }
+                };
+
+                let server = self.server.read().await;
+                if let Some(session) = server.pub_session_find(&session_id) {
+                    session.broadcast(&S2CMessage::NotifyState { session_id, state });
+                }
+
+                CommandOutcome::none()
+            }
+            C2SMessage::LocateSession {
+                session_id,
+                cluster_token,
+                secret,
+                ..
+            } => {
+                let server = self.server.read().await;
+                if !server.verify_cluster_token(&cluster_token).await {
+                    return CommandOutcome::reply(S2CMessage::Error {
+                        request_id,
+                        message: "invalid cluster token".to_string(),
+                    });
+                }
+
+                let result = match server.pub_session_find(&session_id) {
+                    Some(session) if session.is_relay => SessionLocationResult::NotFound,
+                    Some(session) if !session.secret_matches(secret.as_deref()) => {
+                        SessionLocationResult::Unauthorized
+                    }
+                    Some(session) => SessionLocationResult::Found {
+                        node_id: server.node_id().await.unwrap_or_default(),
+                        private: session.is_private(),
+                    },
+                    None => SessionLocationResult::NotFound,
+                };
+
+                CommandOutcome::reply(S2CMessage::SessionLocation {
+                    request_id,
+                    session_id,
+                    result,
+                })
+            }
+            C2SMessage::NegotiateEncoding {
+                encodings, deflate, ..
+            } => {
+                let mut client = self.client.write().await;
+                if client.encoding_negotiated {
+                    return CommandOutcome::reply(S2CMessage::Error {
+                        request_id,
+                        message: "encoding was already negotiated for this connection"
+                            .to_string(),
+                    });
+                }
+
+                let codec = Codec::negotiate(&encodings, deflate);
+                *client.codec.write().await = codec;
+                client.encoding_negotiated = true;
+
+                CommandOutcome::reply(S2CMessage::ResponseNegotiateEncoding {
+                    request_id,
+                    encoding: codec.encoding,
+                    deflate: codec.deflate,
+                })
+            }
+        }
+    }
+}
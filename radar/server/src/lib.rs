@@ -0,0 +1,19 @@
+use std::collections::BTreeSet;
+
+pub mod assets;
+pub mod auth;
+pub mod client;
+pub mod cluster;
+pub mod codec;
+pub mod handler;
+pub mod server;
+
+/// Lifecycle state of a single connected websocket client.
+#[derive(Debug, Clone)]
+pub enum ClientState {
+    Uninitialized,
+    Publisher { session_id: String },
+    /// Subscribed to zero or more sessions at once. A connection is moved
+    /// back to `Uninitialized` once the last subscription is removed.
+    Subscriber { session_ids: BTreeSet<String> },
+}
@@ -1,11 +1,19 @@
 use std::{
-    collections::BTreeMap,
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
     net::SocketAddr,
     path::PathBuf,
     sync::{
+        atomic::{
+            AtomicU32,
+            Ordering,
+        },
         Arc,
         Weak,
     },
+    time::Duration,
 };
 
 use anyhow::anyhow;
@@ -18,11 +26,13 @@ use radar_shared::protocol::{
     C2SMessage,
     ClientEvent,
     S2CMessage,
+    SessionLocationResult,
 };
 use rand::{
     distributions::Alphanumeric,
     Rng,
 };
+use subtle::ConstantTimeEq;
 use tokio::{
     self,
     sync::{
@@ -40,7 +50,17 @@ use warp::{
 };
 
 use crate::{
-    client::PubClient,
+    assets::serve_embedded,
+    auth::PublisherToken,
+    client::{
+        PubClient,
+        Shutdown,
+    },
+    cluster::{
+        ClusterConfig,
+        ClusterManager,
+    },
+    codec::Codec,
     handler::ServerCommandHandler,
     ClientState,
 };
@@ -48,6 +68,12 @@ use crate::{
 pub struct PubSession {
     pub owner_id: u32,
     pub session_id: String,
+    /// `true` if the publisher for this session lives on a different
+    /// cluster node and `owner_id` is therefore not a local client id.
+    pub is_relay: bool,
+    /// Present for private sessions; `pub_session_subscribe` requires a
+    /// matching secret before it will add a subscriber.
+    subscribe_secret: Option<String>,
     subscriber: BTreeMap<u32, mpsc::Sender<S2CMessage>>,
 }
 
@@ -61,6 +87,23 @@ impl PubSession {
     pub fn subscriber_count(&self) -> usize {
         self.subscriber.len()
     }
+
+    pub fn is_private(&self) -> bool {
+        self.subscribe_secret.is_some()
+    }
+
+    /// Whether `secret` satisfies this session's subscribe secret. Always
+    /// `true` for a non-private session, regardless of what was passed.
+    /// Compared in constant time so repeated subscribe attempts can't be
+    /// used to brute-force the secret via response timing.
+    pub fn secret_matches(&self, secret: Option<&str>) -> bool {
+        match &self.subscribe_secret {
+            Some(expected) => secret.is_some_and(|candidate| {
+                expected.as_bytes().ct_eq(candidate.as_bytes()).into()
+            }),
+            None => true,
+        }
+    }
 }
 
 pub enum HttpServeDirectory {
@@ -83,6 +126,14 @@ pub struct RadarServer {
     clients: BTreeMap<u32, Arc<RwLock<PubClient>>>,
     pub_sessions: BTreeMap<String, PubSession>,
 
+    publisher_tokens: Vec<PublisherToken>,
+    cluster: Option<Arc<RwLock<ClusterManager>>>,
+
+    /// How often to ping each client.
+    heartbeat_interval: Duration,
+    /// Consecutive missed pongs after which a client is force-unregistered.
+    heartbeat_miss_threshold: u32,
+
     www_acceptor: Option<JoinHandle<()>>,
 }
 
@@ -95,6 +146,12 @@ impl RadarServer {
             clients: Default::default(),
             pub_sessions: Default::default(),
 
+            publisher_tokens: Default::default(),
+            cluster: None,
+
+            heartbeat_interval: Duration::from_secs(15),
+            heartbeat_miss_threshold: 3,
+
             www_acceptor: None,
         };
 
@@ -104,6 +161,29 @@ impl RadarServer {
         })
     }
 
+    /// Join a cluster of peer radar servers so sessions published on a peer
+    /// can be subscribed to from here. Must be called before `listen_http`
+    /// is used to accept subscribers, as it also namespaces newly created
+    /// session ids with the cluster node id.
+    pub fn join_cluster(&mut self, config: ClusterConfig) {
+        let cluster = ClusterManager::new(self.ref_self.clone(), config);
+        self.cluster = Some(cluster);
+    }
+
+    /// Set the publisher tokens `pub_session_create` will accept. Replaces
+    /// any previously configured tokens.
+    pub fn set_publisher_tokens(&mut self, tokens: Vec<PublisherToken>) {
+        self.publisher_tokens = tokens;
+    }
+
+    /// Configure the websocket heartbeat. `interval` controls how often a
+    /// client is pinged; a client that fails to pong back `miss_threshold`
+    /// times in a row is force-unregistered.
+    pub fn set_heartbeat(&mut self, interval: Duration, miss_threshold: u32) {
+        self.heartbeat_interval = interval;
+        self.heartbeat_miss_threshold = miss_threshold;
+    }
+
     pub async fn listen_http(
         &mut self,
         addr: impl Into<SocketAddr>,
@@ -114,6 +194,8 @@ impl RadarServer {
         }
 
         let server = self.ref_self.clone();
+        let heartbeat_interval = self.heartbeat_interval;
+        let heartbeat_miss_threshold = self.heartbeat_miss_threshold;
         let ws_route = warp::any()
             .and(warp::path("subscribe").or(warp::path("publish")))
             .and(warp::addr::remote())
@@ -129,6 +211,24 @@ impl RadarServer {
                     let (message_tx, mut message_tx_rx) = mpsc::channel(16);
                     let (message_rx_tx, message_rx) = mpsc::channel(16);
 
+                    /* kept so the rx loop can report parse errors even though
+                     * `message_tx` itself is moved into the `PubClient` below */
+                    let error_tx = message_tx.clone();
+
+                    /* shared between the rx loop (reset on pong) and the tx
+                     * loop (incremented on every heartbeat tick) */
+                    let missed_pongs = Arc::new(AtomicU32::new(0));
+
+                    /* shared between the handler (on `NegotiateEncoding`),
+                     * the rx loop (to decode incoming frames) and the tx
+                     * loop (to encode outgoing ones) */
+                    let codec = Arc::new(RwLock::new(Codec::default()));
+
+                    /* signalled by `register_client`'s command loop (via
+                     * `CommandOutcome::close`) to tear down the rx/tx loops
+                     * below, e.g. after rejecting an incompatible `Version` */
+                    let shutdown = Arc::new(Shutdown::default());
+
                     {
                         let server = match server.upgrade() {
                             Some(server) => server,
@@ -144,7 +244,12 @@ impl RadarServer {
                         let mut server = server.write().await;
                         let client_fut = server
                             .register_client(
-                                PubClient::new(message_tx, address.clone()),
+                                PubClient::new(
+                                    message_tx,
+                                    address.clone(),
+                                    codec.clone(),
+                                    shutdown.clone(),
+                                ),
                                 message_rx,
                             )
                             .await;
@@ -157,29 +262,53 @@ impl RadarServer {
 
                         let rx_loop = tokio::spawn({
                             let message_rx_tx = message_rx_tx.clone();
+                            let error_tx = error_tx.clone();
+                            let missed_pongs = missed_pongs.clone();
+                            let codec = codec.clone();
+                            let shutdown = shutdown.clone();
                             async move {
-                                while let Some(message) = rx.next().await {
+                                loop {
+                                    let message = tokio::select! {
+                                        message = rx.next() => message,
+                                        _ = shutdown.wait() => break,
+                                    };
+
                                     let message = match message {
-                                        Ok(message) => message,
-                                        Err(err) => {
+                                        Some(Ok(message)) => message,
+                                        Some(Err(err)) => {
                                             let _ = message_rx_tx
                                                 .send(ClientEvent::RecvError(err.into()))
                                                 .await;
                                             break;
                                         }
+                                        None => break,
                                     };
 
-                                    if message.is_text() {
-                                        let message =
-                                            match serde_json::from_slice(message.as_bytes()) {
-                                                Ok(message) => message,
-                                                Err(err) => {
-                                                    let _ = message_rx_tx
-                                                        .send(ClientEvent::RecvError(err.into()))
-                                                        .await;
-                                                    break;
-                                                }
-                                            };
+                                    if message.is_pong() {
+                                        missed_pongs.store(0, Ordering::SeqCst);
+                                        continue;
+                                    }
+
+                                    if message.is_text() || message.is_binary() {
+                                        let decoded = codec.read().await.decode(message.as_bytes());
+                                        let message = match decoded {
+                                            Ok(message) => message,
+                                            Err(err) => {
+                                                /* each frame is independently delimited, so a
+                                                 * single malformed one shouldn't tear down the
+                                                 * whole connection - report it and keep going */
+                                                let _ = error_tx
+                                                    .send(S2CMessage::Error {
+                                                        request_id: None,
+                                                        message: format!(
+                                                            "failed to parse message: {}",
+                                                            err
+                                                        ),
+                                                    })
+                                                    .await;
+                                                continue;
+                                            }
+                                        };
 
                                         if let Err(err) = {
                                             message_rx_tx
@@ -198,23 +327,64 @@ impl RadarServer {
 
                         let tx_loop = tokio::spawn({
                             let message_rx_tx = message_rx_tx.clone();
+                            let missed_pongs = missed_pongs.clone();
+                            let codec = codec.clone();
+                            let shutdown = shutdown.clone();
                             async move {
-                                while let Some(message) = message_tx_rx.recv().await {
-                                    let encoded = match serde_json::to_string(&message) {
-                                        Ok(message) => message,
-                                        Err(err) => {
-                                            let _ = message_rx_tx
-                                                .send(ClientEvent::SendError(err.into()))
-                                                .await;
-                                            break;
-                                        }
-                                    };
+                                let mut heartbeat = tokio::time::interval(heartbeat_interval);
+                                /* the first tick fires immediately, we don't want to ping right away */
+                                heartbeat.tick().await;
+
+                                loop {
+                                    tokio::select! {
+                                        /* polled in order, so a response queued right before
+                                         * `shutdown` is signalled (e.g. a rejected `Version`)
+                                         * is still sent before the loop stops */
+                                        biased;
+                                        message = message_tx_rx.recv() => {
+                                            let Some(message) = message else { break; };
+                                            let codec = *codec.read().await;
+                                            let encoded = match codec.encode(&message) {
+                                                Ok(encoded) => encoded,
+                                                Err(err) => {
+                                                    let _ = message_rx_tx
+                                                        .send(ClientEvent::SendError(err))
+                                                        .await;
+                                                    break;
+                                                }
+                                            };
+
+                                            let frame = if codec.is_binary() {
+                                                Message::binary(encoded)
+                                            } else {
+                                                Message::text(String::from_utf8_lossy(&encoded).into_owned())
+                                            };
 
-                                    if let Err(err) = tx.send(Message::text(encoded)).await {
-                                        let _ = message_rx_tx
-                                            .send(ClientEvent::SendError(err.into()))
-                                            .await;
-                                        break;
+                                            if let Err(err) = tx.send(frame).await {
+                                                let _ = message_rx_tx
+                                                    .send(ClientEvent::SendError(err.into()))
+                                                    .await;
+                                                break;
+                                            }
+                                        }
+                                        _ = heartbeat.tick() => {
+                                            if missed_pongs.fetch_add(1, Ordering::SeqCst)
+                                                >= heartbeat_miss_threshold
+                                            {
+                                                let _ = message_rx_tx
+                                                    .send(ClientEvent::RecvError(anyhow!(
+                                                        "client missed {} consecutive heartbeats",
+                                                        heartbeat_miss_threshold
+                                                    )))
+                                                    .await;
+                                                break;
+                                            }
+
+                                            if tx.send(Message::ping(Vec::new())).await.is_err() {
+                                                break;
+                                            }
+                                        }
+                                        _ = shutdown.wait() => break,
                                     }
                                 }
                             }
@@ -240,9 +410,10 @@ impl RadarServer {
                 .or(warp::fs::file(path.join("index.html")))
                 .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
                 .boxed(),
-            HttpServeDirectory::Bundled => {
-                anyhow::bail!("bundled is currently not supported");
-            }
+            HttpServeDirectory::Bundled => ws_route
+                .or(serve_embedded())
+                .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+                .boxed(),
             HttpServeDirectory::None => ws_route
                 .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
                 .boxed(),
@@ -270,11 +441,15 @@ impl RadarServer {
             ClientState::Publisher { session_id } => {
                 if let Some(session) = self.pub_sessions.remove(&session_id) {
                     log::info!("Session {} closed", session_id);
-                    session.broadcast(&S2CMessage::NotifySessionClosed);
+                    session.broadcast(&S2CMessage::NotifySessionClosed {
+                        session_id: session_id.clone(),
+                    });
                 }
             }
-            ClientState::Subscriber { session_id } => {
-                self.pub_session_unsubscribe(&session_id, client_id).await;
+            ClientState::Subscriber { session_ids } => {
+                for session_id in session_ids {
+                    self.pub_session_unsubscribe(&session_id, client_id).await;
+                }
             }
             ClientState::Uninitialized => { /* Nothing to do! */ }
         };
@@ -310,8 +485,16 @@ impl RadarServer {
             while let Some(event) = rx.recv().await {
                 match event {
                     ClientEvent::RecvMessage(command) => {
-                        let result = command_handler.handle_command(command).await;
-                        client.read().await.send_command(result);
+                        let outcome = command_handler.handle_command(command).await;
+                        let client = client.read().await;
+                        client.send_command(outcome.response);
+                        if outcome.close {
+                            /* tears down the rx/tx websocket tasks so the
+                             * connection actually closes instead of just
+                             * being dropped from the server's bookkeeping */
+                            client.shutdown.signal();
+                            break;
+                        }
                     }
                     ClientEvent::RecvError(err) => {
                         log::debug!("Client {} recv error: {}", command_handler.client_id, err);
@@ -333,28 +516,54 @@ impl RadarServer {
         }
     }
 
-    pub async fn pub_session_create(&mut self, owner_id: u32) -> Option<&PubSession> {
+    pub async fn pub_session_create(
+        &mut self,
+        owner_id: u32,
+        token: &str,
+        private: bool,
+    ) -> PubSessionCreateResult {
         let owner = match self.clients.get(&owner_id) {
             Some(client) => client,
-            None => return None,
+            None => return PubSessionCreateResult::InvalidClientState,
         };
 
         let mut owner = owner.write().await;
         if !matches!(owner.state, ClientState::Uninitialized) {
-            return None;
+            return PubSessionCreateResult::InvalidClientState;
         }
 
-        let session_id = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .map(char::from)
-            .take(6)
-            .collect::<String>();
+        if !self
+            .publisher_tokens
+            .iter()
+            .any(|candidate| candidate.matches(token) && candidate.is_valid())
+        {
+            return PubSessionCreateResult::Unauthorized;
+        }
+
+        let session_id = match &self.cluster {
+            Some(cluster) => cluster.read().await.new_session_id(),
+            None => rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .map(char::from)
+                .take(6)
+                .collect::<String>(),
+        };
+
+        let subscribe_secret = private.then(|| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .map(char::from)
+                .take(16)
+                .collect::<String>()
+        });
 
         self.pub_sessions.insert(
             session_id.clone(),
             PubSession {
                 owner_id,
                 session_id: session_id.clone(),
+                is_relay: false,
+                subscribe_secret: subscribe_secret.clone(),
                 subscriber: Default::default(),
             },
         );
@@ -363,32 +572,163 @@ impl RadarServer {
         owner.state = ClientState::Publisher {
             session_id: session_id.clone(),
         };
-        self.pub_sessions.get(&session_id)
+        PubSessionCreateResult::Success {
+            session_id,
+            subscribe_secret,
+        }
     }
 
     pub fn pub_session_find(&self, session_id: &str) -> Option<&PubSession> {
         self.pub_sessions.get(session_id)
     }
 
+    /// Whether `session_id` has a publisher hosted on this node (relayed
+    /// sessions don't count, their origin is elsewhere).
+    pub fn hosts_session(&self, session_id: &str) -> bool {
+        matches!(self.pub_sessions.get(session_id), Some(session) if !session.is_relay)
+    }
+
+    /// This node's cluster id, if it has joined a cluster.
+    pub async fn node_id(&self) -> Option<String> {
+        match &self.cluster {
+            Some(cluster) => Some(cluster.read().await.node_id().to_string()),
+            None => None,
+        }
+    }
+
+    /// Whether `token` matches the configured secret for one of our cluster
+    /// peers. Used to authenticate `LocateSession` queries, which arrive
+    /// over the same websocket route as ordinary clients. Always `false`
+    /// when this node hasn't joined a cluster.
+    pub async fn verify_cluster_token(&self, token: &str) -> bool {
+        match &self.cluster {
+            Some(cluster) => cluster.read().await.is_valid_peer_token(token),
+            None => false,
+        }
+    }
+
+    /// Feature flags advertised to clients during the version handshake.
+    /// Encodings, heartbeat and multi-subscribe are unconditional; the rest depend on how
+    /// this instance was configured.
+    pub fn capabilities(&self) -> Vec<String> {
+        let mut capabilities = vec![
+            "encoding:json".to_string(),
+            "encoding:messagepack".to_string(),
+            "encoding:cbor".to_string(),
+            "deflate".to_string(),
+            "heartbeat".to_string(),
+            "multi-subscribe".to_string(),
+        ];
+
+        if !self.publisher_tokens.is_empty() {
+            capabilities.push("private-sessions".to_string());
+        }
+
+        if self.cluster.is_some() {
+            capabilities.push("cluster".to_string());
+        }
+
+        capabilities
+    }
+
+    /// If `session_id` is not known locally, ask the cluster which peer
+    /// hosts it and, if found, register a local relay session that mirrors
+    /// that peer's broadcasts so local clients can subscribe to it as if it
+    /// were hosted here. `secret` is forwarded to the owning node so it can
+    /// authorize a private session before we relay it; the relay session we
+    /// create mirrors the same secret requirement locally.
+    async fn try_relay_session(
+        &mut self,
+        session_id: &str,
+        secret: Option<&str>,
+    ) -> SessionLocationResult {
+        let Some(cluster) = self.cluster.clone() else {
+            return SessionLocationResult::NotFound;
+        };
+
+        let result = cluster.write().await.locate_session(session_id, secret).await;
+        let SessionLocationResult::Found { node_id, private } = &result else {
+            return result;
+        };
+
+        self.pub_sessions.insert(
+            session_id.to_string(),
+            PubSession {
+                owner_id: 0,
+                session_id: session_id.to_string(),
+                is_relay: true,
+                subscribe_secret: private.then(|| secret.unwrap_or_default().to_string()),
+                subscriber: Default::default(),
+            },
+        );
+
+        cluster
+            .read()
+            .await
+            .subscribe_remote(node_id, session_id, secret)
+            .await;
+
+        result
+    }
+
+    /// Add a peer's reported view count for a relayed session to our own
+    /// local subscriber count and re-broadcast the sum to the local
+    /// subscribers. The peer's count covers its own subscribers, among which
+    /// this node's relay link counts as a single viewer; viewers on other
+    /// relaying nodes are not included, and subscribers connected to the
+    /// origin node only see the origin's own count.
+    pub fn pub_session_relay_view_count(&self, session_id: &str, remote_viewers: usize) {
+        if let Some(session) = self.pub_sessions.get(session_id) {
+            session.broadcast(&S2CMessage::NotifyViewCount {
+                session_id: session_id.to_string(),
+                viewers: session.subscriber_count() + remote_viewers,
+            });
+        }
+    }
+
+    /// Remove a relayed session after the cluster reported its origin
+    /// publisher closed, notifying any local subscribers.
+    pub async fn pub_session_close_relay(&mut self, session_id: &str) {
+        if let Some(session) = self.pub_sessions.remove(session_id) {
+            session.broadcast(&S2CMessage::NotifySessionClosed {
+                session_id: session_id.to_string(),
+            });
+        }
+    }
+
     pub async fn pub_session_unsubscribe(&mut self, session_id: &String, client_id: u32) {
+        let mut drop_relay = false;
         if let Some(session) = self.pub_sessions.get_mut(session_id) {
             session.subscriber.remove(&client_id);
             session.broadcast(&S2CMessage::NotifyViewCount {
+                session_id: session_id.clone(),
                 viewers: session.subscriber_count(),
             });
+            drop_relay = session.is_relay && session.subscriber_count() == 0;
+        }
+
+        if drop_relay {
+            /* unlike a locally-hosted session (which lives until its
+             * publisher disconnects), a relay session has no owner here -
+             * nothing else will ever clean it up once nobody is watching it */
+            self.pub_sessions.remove(session_id);
+            if let (Some(cluster), Some(node_id)) =
+                (&self.cluster, ClusterManager::owning_node(session_id))
+            {
+                cluster.read().await.unsubscribe_remote(node_id, session_id).await;
+            }
         }
 
         if let Some(client) = self.clients.get(&client_id) {
             let mut client = client.write().await;
-            if let ClientState::Subscriber {
-                session_id: client_session_id,
-            } = &client.state
-            {
-                if client_session_id == session_id {
-                    client.state = ClientState::Uninitialized;
+            if let ClientState::Subscriber { session_ids } = &mut client.state {
+                if session_ids.remove(session_id) {
+                    if session_ids.is_empty() {
+                        client.state = ClientState::Uninitialized;
+                    }
                 } else {
                     log::warn!(
-                        "Client state indicates different session id then we unregister the client"
+                        "Client state indicates it is not subscribed to the session we unregister it from"
                     );
                 }
             }
@@ -399,15 +739,28 @@ impl RadarServer {
         &mut self,
         session_id: &String,
         client_id: u32,
+        secret: Option<&str>,
     ) -> PubSessionSubscribeResult {
         let client = match self.clients.get(&client_id) {
-            Some(client) => client,
+            Some(client) => client.clone(),
             None => return PubSessionSubscribeResult::InvalidClientId,
         };
 
         let mut client = client.write().await;
-        if !matches!(client.state, ClientState::Uninitialized) {
-            return PubSessionSubscribeResult::InvalidClientState;
+        match &client.state {
+            ClientState::Uninitialized | ClientState::Subscriber { .. } => {}
+            ClientState::Publisher { .. } => {
+                return PubSessionSubscribeResult::InvalidClientState;
+            }
+        }
+
+        if !self.pub_sessions.contains_key(session_id) {
+            match self.try_relay_session(session_id, secret).await {
+                SessionLocationResult::Unauthorized => {
+                    return PubSessionSubscribeResult::Unauthorized
+                }
+                SessionLocationResult::Found { .. } | SessionLocationResult::NotFound => {}
+            }
         }
 
         let session = match self.pub_sessions.get_mut(session_id) {
@@ -415,17 +768,29 @@ impl RadarServer {
             None => return PubSessionSubscribeResult::InvalidSessionId,
         };
 
+        if !session.secret_matches(secret) {
+            return PubSessionSubscribeResult::Unauthorized;
+        }
+
         session
             .subscriber
             .insert(client.client_id, client.tx.clone());
 
         session.broadcast(&S2CMessage::NotifyViewCount {
+            session_id: session.session_id.clone(),
             viewers: session.subscriber.len(),
         });
 
-        client.state = ClientState::Subscriber {
-            session_id: session.session_id.clone(),
-        };
+        match &mut client.state {
+            ClientState::Subscriber { session_ids } => {
+                session_ids.insert(session.session_id.clone());
+            }
+            _ => {
+                client.state = ClientState::Subscriber {
+                    session_ids: BTreeSet::from([session.session_id.clone()]),
+                };
+            }
+        }
         PubSessionSubscribeResult::Success
     }
 }
@@ -435,4 +800,14 @@ pub enum PubSessionSubscribeResult {
     InvalidClientState,
     InvalidSessionId,
     InvalidClientId,
+    Unauthorized,
+}
+
+pub enum PubSessionCreateResult {
+    Success {
+        session_id: String,
+        subscribe_secret: Option<String>,
+    },
+    InvalidClientState,
+    Unauthorized,
 }
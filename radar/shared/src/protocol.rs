@@ -0,0 +1,208 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// Events the transport layer (websocket rx/tx loops) pushes into a client's
+/// command queue. `T` is the decoded inbound message type.
+pub enum ClientEvent<T> {
+    RecvMessage(T),
+    RecvError(anyhow::Error),
+    SendError(anyhow::Error),
+}
+
+/// The protocol version implemented by this crate. Bumped whenever a
+/// breaking change is made to `C2SMessage`/`S2CMessage`. Exchanged via
+/// `C2SMessage::Version`/`S2CMessage::Version` before any other command.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest client protocol version a server implementing `PROTOCOL_VERSION`
+/// will still accept.
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 1;
+
+/// A wire encoding a client may ask the server to use instead of plain JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+/// Reply payload for `LocateSession`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SessionLocationResult {
+    /// The responding node hosts the session's publisher (and authorized
+    /// the caller's secret, if one was required).
+    Found { node_id: String, private: bool },
+    /// The responding node hosts the session, but the caller's secret
+    /// didn't match its subscribe secret.
+    Unauthorized,
+    /// The responding node doesn't host this session.
+    NotFound,
+}
+
+/// Messages sent from a client to the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum C2SMessage {
+    /// Must be the first message sent on a connection, before
+    /// `InitializePublish`/`InitializeSubscribe`/etc. `protocol_version` is
+    /// this client's `PROTOCOL_VERSION`; the server closes the connection
+    /// with a structured error if it's older than it can support.
+    Version {
+        request_id: Option<String>,
+        client_version: String,
+        protocol_version: u32,
+    },
+
+    /// Become the publisher of a new session. `token` must match one of the
+    /// server's configured publisher tokens. Setting `private` hides the
+    /// session behind a `subscribe_secret` returned in the response, rather
+    /// than allowing anyone who knows the session id to subscribe.
+    InitializePublish {
+        request_id: Option<String>,
+        token: String,
+        #[serde(default)]
+        private: bool,
+    },
+
+    /// Subscribe to an additional session's broadcasts. A connection may be
+    /// subscribed to any number of sessions at once. `secret` is required if
+    /// (and only if) the session was published as private.
+    InitializeSubscribe {
+        request_id: Option<String>,
+        session_id: String,
+        #[serde(default)]
+        secret: Option<String>,
+    },
+
+    /// Stop receiving broadcasts for a previously subscribed session.
+    UnsubscribeSession {
+        request_id: Option<String>,
+        session_id: String,
+    },
+
+    /// Publisher pushes a new radar state to be broadcast to all subscribers.
+    PublishState {
+        request_id: Option<String>,
+        state: serde_json::Value,
+    },
+
+    /// Sent over an inter-node link to ask a peer whether it (locally) hosts
+    /// the publisher for `session_id`. The peer rejects the query if
+    /// `cluster_token` does not match the token it has configured for any
+    /// of its peers. `secret` is the subscribe
+    /// secret the original subscriber presented, forwarded so the owning
+    /// node can authorize it before admitting a cross-node relay for a
+    /// private session.
+    LocateSession {
+        request_id: Option<String>,
+        session_id: String,
+        cluster_token: String,
+        #[serde(default)]
+        secret: Option<String>,
+    },
+
+    /// Advertise the encodings (and optional per-message deflate support)
+    /// this connection can decode. The server picks the mutually supported
+    /// one it ranks highest (the order of `encodings` is not taken into
+    /// account) and replies with
+    /// `S2CMessage::ResponseNegotiateEncoding`; every frame after that
+    /// (including this request's own ack) uses the negotiated codec. May
+    /// only be sent once per connection, as plain JSON, before any other
+    /// codec is active — the server has no way to decode a later attempt to
+    /// renegotiate, since by then frames are expected in the already
+    /// negotiated codec.
+    NegotiateEncoding {
+        request_id: Option<String>,
+        encodings: Vec<Encoding>,
+        #[serde(default)]
+        deflate: bool,
+    },
+}
+
+impl C2SMessage {
+    /// The `request_id` the client attached to this message, if any.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            Self::Version { request_id, .. } => request_id.as_deref(),
+            Self::InitializePublish { request_id, .. } => request_id.as_deref(),
+            Self::InitializeSubscribe { request_id, .. } => request_id.as_deref(),
+            Self::UnsubscribeSession { request_id, .. } => request_id.as_deref(),
+            Self::PublishState { request_id, .. } => request_id.as_deref(),
+            Self::LocateSession { request_id, .. } => request_id.as_deref(),
+            Self::NegotiateEncoding { request_id, .. } => request_id.as_deref(),
+        }
+    }
+}
+
+/// Messages sent from the server to a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum S2CMessage {
+    /// Ack for `C2SMessage::Version`. `capabilities` is a list of opaque
+    /// feature flags (e.g. `"encoding:cbor"`, `"cluster"`) the client can use
+    /// to decide which later commands are safe to send.
+    Version {
+        request_id: Option<String>,
+        server_version: String,
+        protocol_version: u32,
+        capabilities: Vec<String>,
+    },
+
+    ResponsePublishSession {
+        request_id: Option<String>,
+        session_id: String,
+        /// Present only for private sessions; subscribers must present it
+        /// with `InitializeSubscribe` to be let in.
+        subscribe_secret: Option<String>,
+    },
+    ResponseSubscribeSession {
+        request_id: Option<String>,
+        session_id: String,
+    },
+    ResponseUnsubscribeSession {
+        request_id: Option<String>,
+        session_id: String,
+    },
+
+    /// A publisher pushed a new radar state; re-broadcast to subscribers.
+    /// `session_id` lets a connection subscribed to several sessions demux
+    /// which one a frame belongs to.
+    NotifyState {
+        session_id: String,
+        state: serde_json::Value,
+    },
+    NotifyViewCount {
+        session_id: String,
+        viewers: usize,
+    },
+    NotifySessionClosed {
+        session_id: String,
+    },
+
+    /// Reply to `LocateSession`.
+    SessionLocation {
+        request_id: Option<String>,
+        session_id: String,
+        result: SessionLocationResult,
+    },
+
+    /// Ack for `NegotiateEncoding`. The server switches to `encoding` (and
+    /// deflate, if `deflate` is set) immediately, so this ack is itself the
+    /// first frame encoded with the new codec — the client must be ready to
+    /// decode it as such.
+    ResponseNegotiateEncoding {
+        request_id: Option<String>,
+        encoding: Encoding,
+        deflate: bool,
+    },
+
+    /// A command failed or the message could not be parsed. `request_id` is
+    /// `None` when the failure happened before the request could be
+    /// attributed (e.g. malformed JSON).
+    Error {
+        request_id: Option<String>,
+        message: String,
+    },
+}